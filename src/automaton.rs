@@ -0,0 +1,96 @@
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+
+use crate::EzDictItem;
+
+/// A single-pass, longest-match-wins substitution engine built once from a
+/// dictionary's keys.
+///
+/// Replaces the old approach of scanning the text independently for every
+/// `EzDictItem`, which made the result depend on dictionary order whenever
+/// two keys overlapped (e.g. `"abc"` and `"ab"` both matching at the same
+/// position). Matching is `MatchKind::LeftmostLongest`, so at every position
+/// the longest candidate key wins, and matches never overlap: scanning
+/// resumes right after the replaced span.
+pub struct DictAutomaton {
+    ac: Option<AhoCorasick>,
+    values: Vec<String>,
+}
+
+impl Default for DictAutomaton {
+    fn default() -> Self {
+        Self {
+            ac: None,
+            values: Vec::new(),
+        }
+    }
+}
+
+impl DictAutomaton {
+    /// Builds the automaton from `items`' literal entries. Regex entries
+    /// can't be indexed into a trie of exact keys - `EzDict` applies those
+    /// separately after this automaton's single pass.
+    pub fn build(items: &[EzDictItem]) -> Self {
+        let literal_items: Vec<&EzDictItem> = items.iter().filter(|item| item.is_literal()).collect();
+
+        if literal_items.is_empty() {
+            return Self {
+                ac: None,
+                values: Vec::new(),
+            };
+        }
+
+        let ac = AhoCorasickBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(literal_items.iter().map(|item| item.key()))
+            .expect("dictionary keys form a valid automaton");
+
+        Self {
+            ac: Some(ac),
+            values: literal_items
+                .iter()
+                .map(|item| item.value().to_owned())
+                .collect(),
+        }
+    }
+
+    /// Applies every key in this automaton to `text` in a single left-to-right
+    /// pass, replacing each non-overlapping longest match with its value.
+    pub fn apply(&self, text: &mut String) {
+        if let Some(ac) = &self.ac {
+            *text = ac.replace_all(text, &self.values);
+        }
+    }
+}
+
+#[test]
+fn automaton_longest_match_wins() {
+    let items = vec![
+        EzDictItem::new("ab".into(), "SHORT".into()).unwrap(),
+        EzDictItem::new("abc".into(), "LONG".into()).unwrap(),
+    ];
+    let automaton = DictAutomaton::build(&items);
+
+    let mut text = "xabcx".to_string();
+    automaton.apply(&mut text);
+    assert_eq!(text, "xLONGx");
+}
+
+#[test]
+fn automaton_non_overlapping() {
+    let items = vec![EzDictItem::new("aa".into(), "b".into()).unwrap()];
+    let automaton = DictAutomaton::build(&items);
+
+    let mut text = "aaaa".to_string();
+    automaton.apply(&mut text);
+    assert_eq!(text, "bb");
+}
+
+#[test]
+fn automaton_empty_value_deletes() {
+    let items = vec![EzDictItem::new("123".into(), "".into()).unwrap()];
+    let automaton = DictAutomaton::build(&items);
+
+    let mut text = "123def".to_string();
+    automaton.apply(&mut text);
+    assert_eq!(text, "def");
+}
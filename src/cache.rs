@@ -0,0 +1,126 @@
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use serde_cbor::tags::Tagged;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Tag numbers marking the metadata wrapped around a cache file's entries.
+///
+/// These live in CBOR's unassigned/private-use tag space rather than the
+/// IANA-registered range: they only need to stay stable within this crate's
+/// own cache files, not be globally unique. The tags label the metadata but
+/// do not gate loading: `Tagged<T>` also accepts an untagged value. An old
+/// bare `String -> String` cache fails to deserialize as this envelope
+/// because its metadata fields are missing, and this envelope fails to
+/// deserialize as a bare map because its metadata values aren't strings.
+const TAG_FINGERPRINT: u64 = 0xE7A0_0001;
+const TAG_ENGINE_VERSION: u64 = 0xE7A0_0002;
+const TAG_SAVED_AT: u64 = 0xE7A0_0003;
+
+/// On-disk cache container: cached translations plus the dictionary
+/// fingerprint that produced them.
+///
+/// `before_dict`/`after_dict` entries are baked into cached text (the former
+/// before caching even happens, the latter inside the cached closure), so a
+/// cache produced under one dictionary is silently wrong once the user edits
+/// `userdic.yml` or calls `ez_add_before_dict`/`ez_add_after_dict`. Storing
+/// the fingerprint that produced a cache file lets [`CacheFile::load`] throw
+/// away entries that no longer match the dictionary that's about to be used.
+#[derive(Serialize, Deserialize)]
+pub struct CacheFile {
+    fingerprint: Tagged<u64>,
+    engine_version: Tagged<String>,
+    saved_at: Tagged<u64>,
+    entries: FxHashMap<String, String>,
+}
+
+impl CacheFile {
+    fn new(fingerprint: u64, entries: FxHashMap<String, String>) -> Self {
+        let saved_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            fingerprint: Tagged::new(Some(TAG_FINGERPRINT), fingerprint),
+            engine_version: Tagged::new(
+                Some(TAG_ENGINE_VERSION),
+                env!("CARGO_PKG_VERSION").to_string(),
+            ),
+            saved_at: Tagged::new(Some(TAG_SAVED_AT), saved_at),
+            entries,
+        }
+    }
+
+    /// Loads cached entries from `path`, dropping all of them if
+    /// `current_fingerprint` no longer matches the fingerprint stored in the
+    /// file. Returns an empty cache if the file doesn't exist yet.
+    pub fn load(
+        path: &Path,
+        current_fingerprint: u64,
+    ) -> Result<FxHashMap<String, String>, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(FxHashMap::default());
+        }
+
+        let file: CacheFile = serde_cbor::from_reader(fs::File::open(path)?)?;
+
+        if file.fingerprint.value == current_fingerprint {
+            Ok(file.entries)
+        } else {
+            Ok(FxHashMap::default())
+        }
+    }
+
+    pub fn save(
+        path: &Path,
+        fingerprint: u64,
+        entries: FxHashMap<String, String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let file = CacheFile::new(fingerprint, entries);
+        fs::write(path, serde_cbor::to_vec(&file)?)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn cache_file_round_trips_through_save_and_load() {
+    let dir = std::env::temp_dir().join(format!("ezdict_cache_file_test_{:?}", std::thread::current().id()));
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join("cache.cbor");
+    let _ = fs::remove_file(&path);
+
+    let mut entries = FxHashMap::default();
+    entries.insert("foo".to_string(), "bar".to_string());
+    entries.insert("baz".to_string(), "qux".to_string());
+
+    CacheFile::save(&path, 1, entries.clone()).unwrap();
+
+    let loaded = CacheFile::load(&path, 1).unwrap();
+    assert_eq!(loaded, entries);
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn cache_file_drops_entries_on_fingerprint_mismatch() {
+    let dir = std::env::temp_dir().join(format!(
+        "ezdict_cache_file_fingerprint_test_{:?}",
+        std::thread::current().id()
+    ));
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join("cache.cbor");
+    let _ = fs::remove_file(&path);
+
+    let mut entries = FxHashMap::default();
+    entries.insert("foo".to_string(), "bar".to_string());
+    CacheFile::save(&path, 1, entries).unwrap();
+
+    // Dictionary changed since the file was written: loading under the new
+    // fingerprint must not resurrect the stale translations.
+    let loaded = CacheFile::load(&path, 2).unwrap();
+    assert!(loaded.is_empty());
+
+    let _ = fs::remove_file(&path);
+}
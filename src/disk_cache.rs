@@ -0,0 +1,297 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use memmap2::Mmap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+const KEY_TAG_STR: u8 = 0x01;
+
+/// Encodes `key` into a memory-comparable byte string: lexicographic order
+/// of the returned bytes matches `str`'s own ordering, and the encoding is
+/// self-terminating so records can be packed back-to-back in a flat file.
+///
+/// A one-byte type tag goes first (room for future key kinds without
+/// breaking existing files), then the key's UTF-8 bytes with every `0x00`
+/// escaped to `0x00 0xFF`, then a `0x00 0x00` terminator. Escaping interior
+/// zero bytes this way is what lets the terminator stay unambiguous while
+/// preserving order (an escaped `0x00 0xFF` still sorts immediately after a
+/// bare `0x00`, i.e. after whatever it replaced).
+pub fn encode_key(key: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(key.len() + 3);
+    out.push(KEY_TAG_STR);
+
+    for &byte in key.as_bytes() {
+        if byte == 0x00 {
+            out.push(0x00);
+            out.push(0xFF);
+        } else {
+            out.push(byte);
+        }
+    }
+
+    out.push(0x00);
+    out.push(0x00);
+    out
+}
+
+struct IndexEntry {
+    encoded_key: Vec<u8>,
+    value_offset: u32,
+    value_len: u32,
+}
+
+/// Builds the `io::Error` returned when `cache.sorted` doesn't look like a
+/// well-formed sequence of records (truncated write, disk full, hand edit).
+fn corrupt_cache() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed disk cache record")
+}
+
+/// A sorted, append-friendly on-disk cache for translated lines.
+///
+/// Unlike the in-memory `FxHashMap` cache, opening this one doesn't load
+/// every cached translation into RAM: only the (small) index of encoded
+/// keys and value offsets is read up front, and values are read from an
+/// mmap on demand. New entries are buffered in `pending` and only merged
+/// into the sorted file on [`SortedDiskCache::compact`], so repeated
+/// inserts between saves stay O(1) instead of re-sorting the whole file.
+pub struct SortedDiskCache {
+    fingerprint: u64,
+    mmap: Option<Mmap>,
+    index: Vec<IndexEntry>,
+    pending: Vec<(String, String)>,
+}
+
+impl SortedDiskCache {
+    /// Opens the sorted cache at `path`, discarding every entry in it if the
+    /// embedded fingerprint header doesn't match `fingerprint` - mirroring how
+    /// [`crate::cache::CacheFile::load`] gates `cache.cbor`, so an edited
+    /// dictionary can't serve stale translations out of either cache.
+    pub fn open(path: &Path, fingerprint: u64) -> io::Result<Self> {
+        let empty = || Self {
+            fingerprint,
+            mmap: None,
+            index: Vec::new(),
+            pending: Vec::new(),
+        };
+
+        if !path.exists() {
+            return Ok(empty());
+        }
+
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < 8 || (&mmap[0..8]).read_u64::<BigEndian>()? != fingerprint {
+            return Ok(empty());
+        }
+
+        let index = Self::build_index(&mmap)?;
+
+        Ok(Self {
+            fingerprint,
+            mmap: Some(mmap),
+            index,
+            pending: Vec::new(),
+        })
+    }
+
+    fn build_index(mmap: &Mmap) -> io::Result<Vec<IndexEntry>> {
+        let mut index = Vec::new();
+        let mut pos = 8usize;
+
+        while pos < mmap.len() {
+            let key_start = pos;
+
+            // Skip the type tag, then scan for the `0x00 0x00` terminator,
+            // treating `0x00 0xFF` as an escaped literal zero rather than
+            // the end of the key. A truncated or hand-edited file can make
+            // any of these reads run past the mmap, so every access is
+            // bounds-checked: this runs from `ez_init`, and a panic there
+            // would unwind across the FFI boundary and abort the host.
+            pos += 1;
+            loop {
+                let byte = *mmap.get(pos).ok_or_else(corrupt_cache)?;
+                match byte {
+                    0x00 if mmap.get(pos + 1) == Some(&0x00) => {
+                        pos += 2;
+                        break;
+                    }
+                    0x00 => pos += 2,
+                    _ => pos += 1,
+                }
+            }
+
+            let encoded_key = mmap[key_start..pos].to_vec();
+
+            let value_len = mmap
+                .get(pos..pos + 4)
+                .ok_or_else(corrupt_cache)?
+                .read_u32::<BigEndian>()?;
+            pos += 4;
+            let value_offset = pos as u32;
+            pos += value_len as usize;
+
+            if pos > mmap.len() {
+                return Err(corrupt_cache());
+            }
+
+            index.push(IndexEntry {
+                encoded_key,
+                value_offset,
+                value_len,
+            });
+        }
+
+        Ok(index)
+    }
+
+    /// Looks up `key`, checking entries staged since the last compaction
+    /// first (most recent write wins), then binary-searching the sorted
+    /// on-disk index.
+    pub fn get(&self, key: &str) -> Option<String> {
+        if let Some((_, value)) = self.pending.iter().rev().find(|(k, _)| k == key) {
+            return Some(value.clone());
+        }
+
+        let encoded = encode_key(key);
+        let mmap = self.mmap.as_ref()?;
+
+        let idx = self
+            .index
+            .binary_search_by(|entry| entry.encoded_key.as_slice().cmp(&encoded))
+            .ok()?;
+
+        let entry = &self.index[idx];
+        let start = entry.value_offset as usize;
+        let end = start + entry.value_len as usize;
+
+        std::str::from_utf8(&mmap[start..end])
+            .ok()
+            .map(str::to_owned)
+    }
+
+    /// Stages `key` -> `value` for the next [`SortedDiskCache::compact`].
+    pub fn insert(&mut self, key: String, value: String) {
+        self.pending.push((key, value));
+    }
+
+    /// Merges staged inserts with the existing sorted file (pending writes
+    /// win on key collisions) and atomically replaces it with a fresh,
+    /// fully sorted file tagged with `fingerprint`, then reopens the
+    /// mmap/index from the result.
+    ///
+    /// If `fingerprint` no longer matches the fingerprint this cache was
+    /// opened with, the existing on-disk entries are dropped rather than
+    /// merged - the dictionary changed since `open`, so they no longer
+    /// describe a valid translation.
+    pub fn compact(&mut self, path: &Path, fingerprint: u64) -> io::Result<()> {
+        if fingerprint != self.fingerprint {
+            self.mmap = None;
+            self.index.clear();
+            self.fingerprint = fingerprint;
+        }
+
+        if self.pending.is_empty() && self.mmap.is_some() {
+            return Ok(());
+        }
+
+        let mut merged: Vec<(Vec<u8>, String)> = self
+            .index
+            .iter()
+            .filter_map(|entry| {
+                let mmap = self.mmap.as_ref()?;
+                let start = entry.value_offset as usize;
+                let end = start + entry.value_len as usize;
+                let value = std::str::from_utf8(&mmap[start..end]).ok()?.to_owned();
+                Some((entry.encoded_key.clone(), value))
+            })
+            .collect();
+
+        for (key, value) in self.pending.drain(..) {
+            let encoded = encode_key(&key);
+            merged.retain(|(k, _)| k != &encoded);
+            merged.push((encoded, value));
+        }
+
+        merged.sort_unstable_by(|(l, _), (r, _)| l.cmp(r));
+
+        let tmp_path = path.with_extension("sorted.tmp");
+        let mut out = File::create(&tmp_path)?;
+
+        out.write_u64::<BigEndian>(self.fingerprint)?;
+
+        for (encoded_key, value) in &merged {
+            out.write_all(encoded_key)?;
+            out.write_u32::<BigEndian>(value.len() as u32)?;
+            out.write_all(value.as_bytes())?;
+        }
+
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp_path, path)?;
+
+        *self = Self::open(path, self.fingerprint)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn encode_key_preserves_string_order() {
+    let mut keys = vec!["banana", "apple\0", "apple", "app", "cherry"];
+    let mut encoded: Vec<Vec<u8>> = keys.iter().map(|k| encode_key(k)).collect();
+
+    keys.sort_unstable();
+    encoded.sort_unstable();
+
+    let decoded: Vec<Vec<u8>> = keys.iter().map(|k| encode_key(k)).collect();
+    assert_eq!(encoded, decoded);
+}
+
+#[test]
+fn disk_cache_round_trips_through_compact() {
+    let dir = std::env::temp_dir().join(format!("ezdict_disk_cache_test_{:?}", std::thread::current().id()));
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join("cache.sorted");
+    let _ = fs::remove_file(&path);
+
+    let mut cache = SortedDiskCache::open(&path, 1).unwrap();
+    cache.insert("foo".into(), "bar".into());
+    cache.insert("baz".into(), "qux".into());
+    cache.compact(&path, 1).unwrap();
+
+    assert_eq!(cache.get("foo").as_deref(), Some("bar"));
+    assert_eq!(cache.get("baz").as_deref(), Some("qux"));
+    assert_eq!(cache.get("missing"), None);
+
+    let mut reopened = SortedDiskCache::open(&path, 1).unwrap();
+    assert_eq!(reopened.get("foo").as_deref(), Some("bar"));
+
+    reopened.insert("foo".into(), "updated".into());
+    reopened.compact(&path, 1).unwrap();
+    assert_eq!(reopened.get("foo").as_deref(), Some("updated"));
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn disk_cache_drops_entries_on_fingerprint_mismatch() {
+    let dir = std::env::temp_dir().join(format!(
+        "ezdict_disk_cache_fingerprint_test_{:?}",
+        std::thread::current().id()
+    ));
+    let _ = fs::create_dir_all(&dir);
+    let path = dir.join("cache.sorted");
+    let _ = fs::remove_file(&path);
+
+    let mut cache = SortedDiskCache::open(&path, 1).unwrap();
+    cache.insert("foo".into(), "bar".into());
+    cache.compact(&path, 1).unwrap();
+    assert_eq!(cache.get("foo").as_deref(), Some("bar"));
+
+    // Dictionary changed since the file was written: a reopen under the new
+    // fingerprint must not resurrect the stale translation.
+    let reopened = SortedDiskCache::open(&path, 2).unwrap();
+    assert_eq!(reopened.get("foo"), None);
+
+    let _ = fs::remove_file(&path);
+}
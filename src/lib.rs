@@ -8,39 +8,163 @@ use std::fs;
 use std::path::Path;
 use std::ptr::null_mut;
 
-pub struct EzDictItem {
-    key: String,
-    value: String,
+mod automaton;
+mod cache;
+mod disk_cache;
+mod nt_format;
+
+use automaton::DictAutomaton;
+use cache::CacheFile;
+use disk_cache::SortedDiskCache;
+
+/// Identifies the translation/dictionary pipeline version for cache
+/// fingerprinting; bump alongside changes to how `before_dict`/`after_dict`
+/// or the engine itself transform text, so stale caches from an older
+/// pipeline get invalidated too.
+const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Once `EzContext::cache` holds this many entries, `translate_impl` evicts
+/// one before caching a new segment translation. Segment translations also
+/// live in `disk_cache`'s on-disk index, which is what lets history scale
+/// past hundreds of thousands of lines without loading all of it into
+/// memory on every `from_path`. `translate` inserts whole-line results into
+/// the same map without this check, so the map (and `cache.cbor`) can still
+/// grow past this count.
+const HOT_CACHE_CAPACITY: usize = 10_000;
+
+/// A single substitution entry. Most entries are plain literal substrings
+/// (the common case, and the only thing `DictAutomaton`'s trie can index),
+/// but a key prefixed with `re:` opts into regex mode: the rest of the key
+/// is compiled as a pattern and the value becomes a replacement template
+/// supporting `$1`-style capture references.
+pub enum EzDictItem {
+    Literal {
+        key: String,
+        value: String,
+    },
+    Regex {
+        pattern: String,
+        regex: regex::Regex,
+        replacement: String,
+    },
+}
+
+/// An invalid dictionary key: either empty (`key = value` or `re:` with
+/// nothing after the sigil), or a `re:`-prefixed key whose pattern failed to
+/// compile as a regex. Kept as a proper error type (rather than panicking)
+/// since this is reachable from user-editable input: `userdic.yml`/
+/// `userdic.json`, `userdic.nt`, and the `ez_add_before_dict`/
+/// `ez_add_after_dict` FFI calls.
+#[derive(Debug)]
+pub enum InvalidDictKey {
+    Empty,
+    InvalidRegex { pattern: String, source: regex::Error },
+}
+
+impl std::fmt::Display for InvalidDictKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidDictKey::Empty => write!(f, "dictionary key must not be empty"),
+            InvalidDictKey::InvalidRegex { pattern, source } => {
+                write!(f, "invalid regex dictionary key {:?}: {}", pattern, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidDictKey {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InvalidDictKey::Empty => None,
+            InvalidDictKey::InvalidRegex { source, .. } => Some(source),
+        }
+    }
 }
 
 impl EzDictItem {
-    pub fn new(key: String, value: String) -> Self {
-        assert!(!key.is_empty());
-        Self { key, value }
+    const REGEX_SIGIL: &'static str = "re:";
+
+    pub fn new(key: String, value: String) -> Result<Self, InvalidDictKey> {
+        if key.is_empty() {
+            return Err(InvalidDictKey::Empty);
+        }
+
+        match key.strip_prefix(Self::REGEX_SIGIL) {
+            Some(pattern) => {
+                if pattern.is_empty() {
+                    return Err(InvalidDictKey::Empty);
+                }
+
+                let regex = regex::Regex::new(pattern).map_err(|source| InvalidDictKey::InvalidRegex {
+                    pattern: pattern.to_owned(),
+                    source,
+                })?;
+
+                Ok(Self::Regex {
+                    pattern: pattern.to_owned(),
+                    regex,
+                    replacement: value,
+                })
+            }
+            None => Ok(Self::Literal { key, value }),
+        }
     }
 
     pub fn apply(&self, text: &mut String) {
-        let mut prev_pos = 0;
-        while let Some(pos) = twoway::find_str(&text[prev_pos..], &self.key) {
-            text.replace_range(pos..pos + self.key.len(), &self.value);
-            prev_pos = pos + self.value.len();
+        match self {
+            EzDictItem::Literal { key, value } => {
+                let mut prev_pos = 0;
+                while let Some(pos) = twoway::find_str(&text[prev_pos..], key) {
+                    text.replace_range(pos..pos + key.len(), value);
+                    prev_pos = pos + value.len();
+                }
+            }
+            EzDictItem::Regex { regex, replacement, .. } => {
+                if regex.is_match(text) {
+                    *text = regex.replace_all(text, replacement.as_str()).into_owned();
+                }
+            }
         }
     }
 
     #[inline]
     pub fn key(&self) -> &str {
-        &self.key
+        match self {
+            EzDictItem::Literal { key, .. } => key,
+            EzDictItem::Regex { pattern, .. } => pattern,
+        }
     }
 
     #[inline]
     pub fn value(&self) -> &str {
-        &self.value
+        match self {
+            EzDictItem::Literal { value, .. } => value,
+            EzDictItem::Regex { replacement, .. } => replacement,
+        }
+    }
+
+    #[inline]
+    pub fn is_literal(&self) -> bool {
+        matches!(self, EzDictItem::Literal { .. })
+    }
+
+    /// The key as it should be written back to a dictionary file: same as
+    /// [`EzDictItem::key`] for literal entries, but with the `re:` sigil
+    /// restored for regex entries so serializing and re-parsing round-trips
+    /// the variant instead of silently turning it back into a literal.
+    pub fn serialized_key(&self) -> Cow<'_, str> {
+        match self {
+            EzDictItem::Literal { key, .. } => Cow::Borrowed(key.as_str()),
+            EzDictItem::Regex { pattern, .. } => {
+                Cow::Owned(format!("{}{}", Self::REGEX_SIGIL, pattern))
+            }
+        }
     }
 }
 
 #[test]
 fn dict_item_test() {
-    let item = EzDictItem::new("123".into(), "abc".into());
+    let item = EzDictItem::new("123".into(), "abc".into()).unwrap();
     let mut foo = "123def".into();
     item.apply(&mut foo);
     assert_eq!(foo, "abcdef");
@@ -49,12 +173,12 @@ fn dict_item_test() {
 #[test]
 #[should_panic]
 fn dict_item_empty_key_test() {
-    let _item = EzDictItem::new("".into(), "123".into());
+    let _item = EzDictItem::new("".into(), "123".into()).unwrap();
 }
 
 #[test]
 fn dict_item_empty_value_test() {
-    let item = EzDictItem::new("123".into(), "".into());
+    let item = EzDictItem::new("123".into(), "".into()).unwrap();
     let mut foo = "123def".into();
     item.apply(&mut foo);
     assert_eq!(foo, "def");
@@ -62,12 +186,29 @@ fn dict_item_empty_value_test() {
 
 #[test]
 fn dict_item_eq_kv_test() {
-    let item = EzDictItem::new("123".into(), "123".into());
+    let item = EzDictItem::new("123".into(), "123".into()).unwrap();
     let mut foo = "123def".into();
     item.apply(&mut foo);
     assert_eq!(foo, "123def");
 }
 
+#[test]
+fn dict_item_regex_capture_test() {
+    let item = EzDictItem::new("re:(\\w+)さん".into(), "$1씨".into()).unwrap();
+    let mut foo = "타로さん".to_string();
+    item.apply(&mut foo);
+    assert_eq!(foo, "타로씨");
+}
+
+#[test]
+fn dict_item_regex_serialized_key_round_trips() {
+    let item = EzDictItem::new("re:abc".into(), "xyz".into()).unwrap();
+    let roundtripped =
+        EzDictItem::new(item.serialized_key().into_owned(), item.value().into()).unwrap();
+    assert!(!roundtripped.is_literal());
+    assert_eq!(roundtripped.key(), "abc");
+}
+
 #[derive(Serialize, Deserialize, Default)]
 struct EzDict {
     #[serde(default)]
@@ -80,31 +221,96 @@ struct EzDict {
     #[serde(with = "dict_items")]
     #[serde(default)]
     after_dict: Vec<EzDictItem>,
+    #[serde(skip)]
+    before_automaton: DictAutomaton,
+    #[serde(skip)]
+    after_automaton: DictAutomaton,
 }
 
 impl EzDict {
     pub fn sort_before_dict(&mut self) {
-        if !self.sort {
-            return;
+        if self.sort {
+            self.before_dict
+                .sort_unstable_by(|l, r| l.key().cmp(r.key()));
         }
 
-        self.before_dict
-            .sort_unstable_by(|l, r| l.key().cmp(r.key()));
+        self.rebuild_before_automaton();
     }
 
     pub fn sort_after_dict(&mut self) {
-        if !self.sort {
-            return;
+        if self.sort {
+            self.after_dict
+                .sort_unstable_by(|l, r| l.key().cmp(r.key()));
         }
 
-        self.after_dict
-            .sort_unstable_by(|l, r| l.key().cmp(r.key()));
+        self.rebuild_after_automaton();
     }
 
     pub fn sort(&mut self) {
         self.sort_after_dict();
         self.sort_before_dict();
     }
+
+    fn rebuild_before_automaton(&mut self) {
+        self.before_automaton = DictAutomaton::build(&self.before_dict);
+    }
+
+    fn rebuild_after_automaton(&mut self) {
+        self.after_automaton = DictAutomaton::build(&self.after_dict);
+    }
+
+    pub fn push_before(&mut self, item: EzDictItem) {
+        self.before_dict.push(item);
+        self.sort_before_dict();
+    }
+
+    pub fn push_after(&mut self, item: EzDictItem) {
+        self.after_dict.push(item);
+        self.sort_after_dict();
+    }
+
+    pub fn apply_before(&self, text: &mut String) {
+        self.before_automaton.apply(text);
+
+        for item in self.before_dict.iter().filter(|item| !item.is_literal()) {
+            item.apply(text);
+        }
+    }
+
+    pub fn apply_after(&self, text: &mut String) {
+        self.after_automaton.apply(text);
+
+        for item in self.after_dict.iter().filter(|item| !item.is_literal()) {
+            item.apply(text);
+        }
+    }
+
+    /// Hashes the sorted before/after key-value pairs together with
+    /// `engine_id` into a single fingerprint identifying the dictionary
+    /// state that would produce a given set of cached translations.
+    pub fn fingerprint(&self, engine_id: &str) -> u64 {
+        use std::hash::{Hash, Hasher};
+
+        let mut before: Vec<(String, &str)> = self
+            .before_dict
+            .iter()
+            .map(|item| (item.serialized_key().into_owned(), item.value()))
+            .collect();
+        before.sort_unstable();
+
+        let mut after: Vec<(String, &str)> = self
+            .after_dict
+            .iter()
+            .map(|item| (item.serialized_key().into_owned(), item.value()))
+            .collect();
+        after.sort_unstable();
+
+        let mut hasher = fxhash::FxHasher::default();
+        engine_id.hash(&mut hasher);
+        before.hash(&mut hasher);
+        after.hash(&mut hasher);
+        hasher.finish()
+    }
 }
 
 mod dict_items {
@@ -118,7 +324,7 @@ mod dict_items {
         let mut map = s.serialize_map(Some(items.len()))?;
 
         for item in items {
-            map.serialize_entry(item.key(), item.value())?;
+            map.serialize_entry(item.serialized_key().as_ref(), item.value())?;
         }
 
         map.end()
@@ -138,7 +344,7 @@ mod dict_items {
                 let mut ret = Vec::with_capacity(access.size_hint().unwrap_or(10));
 
                 while let Some((key, value)) = access.next_entry()? {
-                    ret.push(EzDictItem::new(key, value));
+                    ret.push(EzDictItem::new(key, value).map_err(serde::de::Error::custom)?);
                 }
 
                 Ok(ret)
@@ -151,7 +357,16 @@ mod dict_items {
 
 pub struct EzContext {
     lib: Container<EzTransLib<'static>>,
+    /// Hot translations (segments and whole lines), kept for fast repeat
+    /// lookups without touching `disk_cache`'s mmap; see
+    /// `HOT_CACHE_CAPACITY` for how far it is bounded. Segment translations
+    /// are also stored in `disk_cache`; whole-line results are kept only here.
     cache: FxHashMap<String, String>,
+    /// Lazily-loaded backing store for all translation history. Only the
+    /// (small) index of keys and value offsets is read up front; values
+    /// come from an mmap on demand. Consulted on a `cache` miss before
+    /// falling back to the translation engine itself.
+    disk_cache: SortedDiskCache,
     dict: EzDict,
     encode_buffer: Vec<u8>,
     string_buffer: String,
@@ -162,19 +377,23 @@ impl EzContext {
         lib: Container<EzTransLib<'static>>,
         path: &Path,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let cache_path = path.join("cache.msgpack");
+        let cache_path = path.join("cache.cbor");
+        let disk_cache_path = path.join("cache.sorted");
         let dict_path = path.join("userdic.yml");
         let json_dict_path = path.join("userdic.json");
+        let nt_dict_path = path.join("userdic.nt");
 
-        let mut cache = if cache_path.exists() {
-            rmp_serde::from_read(fs::File::open(cache_path)?)?
-        } else {
-            FxHashMap::default()
-        };
+        let mut dict = if nt_dict_path.exists() {
+            let src = fs::read_to_string(&nt_dict_path)?;
+            let nt_dict = nt_format::parse(&src)
+                .map_err(|err| format!("{}: {}", nt_dict_path.display(), err))?;
 
-        cache.insert(String::new(), String::new());
-
-        let mut dict = if dict_path.exists() {
+            EzDict {
+                before_dict: nt_dict.before_dict,
+                after_dict: nt_dict.after_dict,
+                ..EzDict::default()
+            }
+        } else if dict_path.exists() {
             serde_yaml::from_reader(fs::File::open(dict_path)?)?
         } else if json_dict_path.exists() {
             serde_json::from_reader(fs::File::open(json_dict_path)?)?
@@ -184,34 +403,60 @@ impl EzContext {
 
         dict.sort();
 
+        let mut cache = CacheFile::load(&cache_path, dict.fingerprint(ENGINE_VERSION))?;
+
+        cache.insert(String::new(), String::new());
+
+        let disk_cache = SortedDiskCache::open(&disk_cache_path, dict.fingerprint(ENGINE_VERSION))?;
+
         Ok(Self {
             lib,
             cache,
+            disk_cache,
             dict,
             encode_buffer: Vec::with_capacity(8192),
             string_buffer: String::new(),
         })
     }
 
-    pub fn save_to(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-        let cache_path = path.join("cache.msgpack");
+    pub fn save_to(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let cache_path = path.join("cache.cbor");
+        let disk_cache_path = path.join("cache.sorted");
         let dict_path = path.join("userdic.yml");
 
-        use std::fs::write;
-
-        write(cache_path, rmp_serde::to_vec(&self.cache)?)?;
-        write(dict_path, serde_yaml::to_vec(&self.dict)?)?;
+        CacheFile::save(
+            &cache_path,
+            self.dict.fingerprint(ENGINE_VERSION),
+            self.cache.clone(),
+        )?;
+        self.disk_cache
+            .compact(&disk_cache_path, self.dict.fingerprint(ENGINE_VERSION))?;
+        fs::write(dict_path, serde_yaml::to_vec(&self.dict)?)?;
 
         Ok(())
     }
 
     fn translate_impl(&mut self, text: &str) -> &str {
+        if self.cache.len() >= HOT_CACHE_CAPACITY && !self.cache.contains_key(text) {
+            // Simple unordered eviction rather than LRU bookkeeping: this
+            // cache only exists to avoid re-hitting disk_cache/the engine
+            // for hot lookups, not to guarantee which entries stay hot.
+            if let Some(evict_key) = self.cache.keys().next().cloned() {
+                self.cache.remove(&evict_key);
+            }
+        }
+
         let dict = &mut self.dict;
         let lib = &self.lib;
         let buf = &mut self.encode_buffer;
         let str_buf = &mut self.string_buffer;
+        let disk_cache = &mut self.disk_cache;
 
         self.cache.entry(text.into()).or_insert_with(move || {
+            if let Some(cached) = disk_cache.get(text) {
+                return cached;
+            }
+
             str_buf.push_str(text);
 
             let mut encoder = SHIFT_JIS.new_encoder();
@@ -245,9 +490,9 @@ impl EzContext {
             let (_decoder_ret, _) =
                 decoder.decode_to_string_without_replacement(translated, &mut ret, true);
 
-            for after in dict.after_dict.iter() {
-                after.apply(&mut ret);
-            }
+            dict.apply_after(&mut ret);
+
+            disk_cache.insert(text.to_string(), ret.clone());
 
             ret
         })
@@ -263,9 +508,7 @@ impl EzContext {
             {
                 let mut text = text.into();
 
-                for before in self.dict.before_dict.iter() {
-                    before.apply(&mut text);
-                }
+                self.dict.apply_before(&mut text);
 
                 let mut prev_pos = 0;
                 let mut is_in_japanese = is_japanese(text.chars().next().unwrap());
@@ -371,11 +614,10 @@ pub unsafe extern "C" fn ez_add_before_dict(
     let key = utf16_to_string(key, key_len);
     let value = utf16_to_string(value, value_len);
 
-    (*ctx)
-        .dict
-        .before_dict
-        .push(EzDictItem::new(key.into_owned(), value.into_owned()));
-    (*ctx).dict.sort_before_dict();
+    match EzDictItem::new(key.into_owned(), value.into_owned()) {
+        Ok(item) => (*ctx).dict.push_before(item),
+        Err(err) => eprintln!("Add before dict err: {}", err),
+    }
 }
 
 #[no_mangle]
@@ -389,11 +631,10 @@ pub unsafe extern "C" fn ez_add_after_dict(
     let key = utf16_to_string(key, key_len);
     let value = utf16_to_string(value, value_len);
 
-    (*ctx)
-        .dict
-        .after_dict
-        .push(EzDictItem::new(key.into_owned(), value.into_owned()));
-    (*ctx).dict.sort_after_dict();
+    match EzDictItem::new(key.into_owned(), value.into_owned()) {
+        Ok(item) => (*ctx).dict.push_after(item),
+        Err(err) => eprintln!("Add after dict err: {}", err),
+    }
 }
 
 #[no_mangle]
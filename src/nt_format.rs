@@ -0,0 +1,296 @@
+//! Parser for `userdic.nt`, a human-authored alternative to the flat
+//! YAML/JSON dictionary maps: entries can be clustered into named groups
+//! (e.g. a character-name glossary) under a `before { ... }` or
+//! `after { ... }` section, and authoring order is preserved so it
+//! interacts predictably with `EzDict`'s `sort` flag.
+//!
+//! ```text
+//! before {
+//!     # honorifics glossary
+//!     honorifics {
+//!         さん = 씨
+//!         くん = 군
+//!     }
+//!
+//!     foo = bar
+//! }
+//!
+//! after {
+//!     baz = qux
+//! }
+//! ```
+
+use nom::branch::alt;
+use nom::bytes::complete::{escaped_transform, is_not, tag, take_till1};
+use nom::character::complete::{char, multispace1, none_of};
+use nom::combinator::{map, map_res, value};
+use nom::multi::many0;
+use nom::sequence::{delimited, pair, preceded, separated_pair};
+use nom::IResult;
+
+use crate::EzDictItem;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NtParseError {
+    /// The input ended while a brace, quote, or entry was still open.
+    Incomplete,
+    /// Parsing finished, but characters remained afterwards that weren't
+    /// consumed by any section.
+    TrailingGarbage { offset: usize },
+    /// A token didn't match the grammar at the given byte offset.
+    Syntax { offset: usize },
+}
+
+impl std::fmt::Display for NtParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NtParseError::Incomplete => write!(f, "unexpected end of input"),
+            NtParseError::TrailingGarbage { offset } => {
+                write!(f, "trailing garbage at byte {}", offset)
+            }
+            NtParseError::Syntax { offset } => write!(f, "syntax error at byte {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for NtParseError {}
+
+/// A dictionary parsed from `.nt` source, split into before/after sections
+/// in authoring order.
+#[derive(Default)]
+pub struct NtDict {
+    pub before_dict: Vec<EzDictItem>,
+    pub after_dict: Vec<EzDictItem>,
+}
+
+pub fn parse(input: &str) -> Result<NtDict, NtParseError> {
+    let mut dict = NtDict::default();
+    let mut rest = skip_trivia(input);
+    let mut parsed_any_section = false;
+
+    while !rest.is_empty() {
+        match section(rest) {
+            Ok((next_rest, (name, entries))) => {
+                match name {
+                    "before" => dict.before_dict.extend(entries),
+                    "after" => dict.after_dict.extend(entries),
+                    _ => return Err(NtParseError::Syntax { offset: offset_of(input, rest) }),
+                }
+                rest = skip_trivia(next_rest);
+                parsed_any_section = true;
+            }
+            Err(nom::Err::Incomplete(_)) => return Err(NtParseError::Incomplete),
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                // `alt`/`many0` backtrack all-or-nothing, so a failure deep
+                // inside a nested group reports its position at the start
+                // of whatever construct gave up, not at the true end of
+                // input. Rather than trust that position, scan the whole
+                // source for unterminated braces/quotes directly - that's
+                // what "truncated mid-construct" actually looks like,
+                // regardless of how deep the parser had recursed.
+                if input_looks_truncated(input) {
+                    return Err(NtParseError::Incomplete);
+                } else if parsed_any_section {
+                    return Err(NtParseError::TrailingGarbage {
+                        offset: offset_of(input, rest),
+                    });
+                } else {
+                    return Err(NtParseError::Syntax {
+                        offset: offset_of(input, e.input),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(dict)
+}
+
+fn offset_of<'a>(original: &'a str, rest: &'a str) -> usize {
+    original.len() - rest.len()
+}
+
+/// Scans the raw source for unterminated `{`/`}` nesting or an unterminated
+/// `"..."` string, ignoring `#` comments. Used to tell "the file was cut off
+/// mid-construct" apart from "the file is malformed" without depending on
+/// where a backtracking parser happened to give up.
+fn input_looks_truncated(input: &str) -> bool {
+    let mut depth: i32 = 0;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                let mut closed = false;
+
+                while let Some(c) = chars.next() {
+                    if c == '\\' {
+                        chars.next();
+                    } else if c == '"' {
+                        closed = true;
+                        break;
+                    }
+                }
+
+                if !closed {
+                    return true;
+                }
+            }
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth > 0
+}
+
+/// `# ...` line comments and whitespace, skipped between every token.
+fn skip_trivia(input: &str) -> &str {
+    fn trivia_piece(input: &str) -> IResult<&str, ()> {
+        alt((
+            value((), multispace1),
+            value((), pair(char('#'), many0(none_of("\n")))),
+        ))(input)
+    }
+
+    many0(trivia_piece)(input)
+        .map(|(rest, _)| rest)
+        .unwrap_or(input)
+}
+
+fn identifier(input: &str) -> IResult<&str, String> {
+    alt((quoted_string, bare_word))(input)
+}
+
+fn bare_word(input: &str) -> IResult<&str, String> {
+    map(
+        take_till1(|c: char| c.is_whitespace() || matches!(c, '{' | '}' | '=' | '#')),
+        str::to_owned,
+    )(input)
+}
+
+fn quoted_string(input: &str) -> IResult<&str, String> {
+    delimited(
+        char('"'),
+        escaped_transform(
+            is_not("\"\\"),
+            '\\',
+            alt((value("\"", tag("\"")), value("\\", tag("\\")))),
+        ),
+        char('"'),
+    )(input)
+}
+
+/// `key = value`
+fn entry(input: &str) -> IResult<&str, EzDictItem> {
+    map_res(
+        separated_pair(
+            preceded(skip_trivia_nom, identifier),
+            preceded(skip_trivia_nom, char('=')),
+            preceded(skip_trivia_nom, identifier),
+        ),
+        |(key, value)| EzDictItem::new(key, value),
+    )(input)
+}
+
+/// A named group of entries: `name { key = value ... }`. The group name is
+/// purely organizational - its entries are flattened into the enclosing
+/// section in authoring order.
+fn group(input: &str) -> IResult<&str, Vec<EzDictItem>> {
+    preceded(
+        skip_trivia_nom,
+        map(
+            pair(
+                identifier,
+                preceded(
+                    skip_trivia_nom,
+                    delimited(
+                        char('{'),
+                        many0(alt((
+                            map(entry, |item| vec![item]),
+                            group,
+                        ))),
+                        preceded(skip_trivia_nom, char('}')),
+                    ),
+                ),
+            ),
+            |(_, groups)| groups.into_iter().flatten().collect(),
+        ),
+    )(input)
+}
+
+/// `before { ... }` or `after { ... }`.
+fn section(input: &str) -> IResult<&str, (&str, Vec<EzDictItem>)> {
+    pair(
+        alt((tag("before"), tag("after"))),
+        preceded(
+            skip_trivia_nom,
+            delimited(
+                char('{'),
+                map(
+                    many0(alt((map(entry, |item| vec![item]), group))),
+                    |groups| groups.into_iter().flatten().collect(),
+                ),
+                preceded(skip_trivia_nom, char('}')),
+            ),
+        ),
+    )(input)
+}
+
+/// Adapter so `skip_trivia` (which never fails) can be used inside a nom
+/// parser chain.
+fn skip_trivia_nom(input: &str) -> IResult<&str, ()> {
+    Ok((skip_trivia(input), ()))
+}
+
+#[test]
+fn parses_flat_entries() {
+    let dict = parse("before { foo = bar }\nafter { baz = qux }").unwrap();
+    assert_eq!(dict.before_dict.len(), 1);
+    assert_eq!(dict.before_dict[0].key(), "foo");
+    assert_eq!(dict.before_dict[0].value(), "bar");
+    assert_eq!(dict.after_dict[0].key(), "baz");
+}
+
+#[test]
+fn parses_nested_groups_and_comments() {
+    let src = r#"
+        before {
+            # honorifics glossary
+            honorifics {
+                a = b
+                c = d
+            }
+            e = f
+        }
+    "#;
+    let dict = parse(src).unwrap();
+    let keys: Vec<&str> = dict.before_dict.iter().map(EzDictItem::key).collect();
+    assert_eq!(keys, ["a", "c", "e"]);
+}
+
+#[test]
+fn reports_syntax_error_offset() {
+    let err = parse("before { foo bar }").unwrap_err();
+    assert_eq!(err, NtParseError::Syntax { offset: 9 });
+}
+
+#[test]
+fn reports_incomplete_unterminated_section() {
+    let err = parse("before { foo = bar").unwrap_err();
+    assert_eq!(err, NtParseError::Incomplete);
+}
+
+#[test]
+fn reports_incomplete_unterminated_nested_group() {
+    let err = parse("before { honorifics { a = b").unwrap_err();
+    assert_eq!(err, NtParseError::Incomplete);
+}